@@ -0,0 +1,85 @@
+//! A trivial in-memory [`ConfigStore`] backed by a `HashMap`.
+//!
+//! This is the backend the example in `main.rs` uses to stand in for flash.
+//! Real targets are expected to bring their own [`ConfigStore`] impl backed
+//! by on-device storage (e.g. a `sequential-storage`-style flash crate);
+//! nothing else in this crate needs to change to support that.
+
+use std::collections::HashMap;
+
+/// Abstraction over a key/value storage backend.
+///
+/// Keys are UTF-8 strings (short, `/`-separated paths like
+/// `"encabulator/config"`); values are opaque CBOR-encoded bytes. Every
+/// method is `async` so that backends that talk to hardware over a bus
+/// (SPI NOR, I2C EEPROM, ...) can yield while waiting on it instead of
+/// blocking the caller.
+///
+/// `ConfigStore` is only ever used generically (`impl ConfigStore` /
+/// `S: ConfigStore`), never as `dyn ConfigStore`, so the usual objection to
+/// `async fn` in public traits — that it can't be made object-safe or have
+/// `Send` pinned down — doesn't apply here.
+#[allow(async_fn_in_trait)]
+pub trait ConfigStore {
+    /// Error type returned by this backend.
+    type Error;
+
+    /// Fetch the bytes currently stored under `key`, if any.
+    async fn fetch(&self, key: &str) -> Result<Option<&[u8]>, Self::Error>;
+
+    /// Store `val` under `key`, overwriting any previous value.
+    async fn store(&mut self, key: &str, val: &[u8]) -> Result<(), Self::Error>;
+
+    /// Remove any value stored under `key`.
+    async fn remove(&mut self, key: &str) -> Result<(), Self::Error>;
+
+    /// List every key currently present in the store.
+    async fn list_keys(&self) -> Result<Vec<String>, Self::Error>;
+}
+
+impl ConfigStore for HashMap<String, Vec<u8>> {
+    type Error = core::convert::Infallible;
+
+    async fn fetch(&self, key: &str) -> Result<Option<&[u8]>, Self::Error> {
+        Ok(self.get(key).map(Vec::as_slice))
+    }
+
+    async fn store(&mut self, key: &str, val: &[u8]) -> Result<(), Self::Error> {
+        self.insert(key.to_string(), val.to_vec());
+        Ok(())
+    }
+
+    async fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+        self.remove(key);
+        Ok(())
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.keys().cloned().collect())
+    }
+}
+
+/// Blanket adapter so a `&mut impl ConfigStore` can be passed anywhere a
+/// [`ConfigStore`] is expected, without the caller giving up ownership.
+impl<S> ConfigStore for &mut S
+where
+    S: ConfigStore,
+{
+    type Error = S::Error;
+
+    async fn fetch(&self, key: &str) -> Result<Option<&[u8]>, Self::Error> {
+        S::fetch(self, key).await
+    }
+
+    async fn store(&mut self, key: &str, val: &[u8]) -> Result<(), Self::Error> {
+        S::store(self, key, val).await
+    }
+
+    async fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+        S::remove(self, key).await
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>, Self::Error> {
+        S::list_keys(self).await
+    }
+}
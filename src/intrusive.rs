@@ -0,0 +1,498 @@
+//! Registry of live configuration nodes.
+//!
+//! Each [`StorageListNode`] is meant to live for `'static`, usually as a
+//! `static` item declared right next to the task that owns it.
+//! [`StorageListNode::attach`] registers a reference to the node with the
+//! owning [`StorageList`] so that [`StorageList::process_reads`] and
+//! [`StorageList::process_writes`] can find it later. The list holds these
+//! references in a heap-allocated `Vec`; it is not an intrusive list.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+use minicbor::{CborLen, Decode, Encode};
+use mutex::raw_impls::cs::CriticalSectionRawMutex;
+
+use crate::hashmap::ConfigStore;
+
+/// Marker for raw mutex implementations usable with [`StorageList`].
+///
+/// Real targets plug in a critical-section or hardware mutex; all
+/// `StorageList` needs from one is `Send + Sync`.
+pub trait RawMutex: Send + Sync + 'static {}
+
+impl RawMutex for CriticalSectionRawMutex {}
+
+/// Error returned when a node could not be attached to a list.
+#[derive(Debug)]
+pub struct AttachError;
+
+/// Type-erased view of a [`StorageListNode`], used internally so
+/// [`StorageList`] can hold nodes of differing `T` in one list, and by
+/// [`crate::txn::Transaction`] to stage writes across differing `T`.
+pub(crate) trait ErasedNode: Send + Sync {
+    fn key(&self) -> &'static str;
+    /// Attempt to decode `bytes` into this node's value, returning `true`
+    /// if the node now holds a (possibly unchanged) decoded value. A no-op
+    /// that returns `false` if the node is currently dirty — an unflushed
+    /// write is pending and `bytes` (read from the backend before that
+    /// write landed) would clobber it.
+    fn load_bytes(&self, bytes: &[u8]) -> bool;
+    /// Encode the current value if it has been written since the last
+    /// flush. Does *not* clear the dirty flag — call [`mark_clean`]
+    /// (only) once the bytes have actually been persisted, so a failed
+    /// write leaves the node dirty for the next retry.
+    ///
+    /// [`mark_clean`]: ErasedNode::mark_clean
+    fn dirty_bytes(&self) -> Option<Vec<u8>>;
+    /// Clear the dirty flag reported by a prior [`dirty_bytes`](ErasedNode::dirty_bytes) call.
+    fn mark_clean(&self);
+}
+
+struct Inner<T> {
+    value: T,
+    dirty: bool,
+    /// Bumped every time `value` changes via `process_reads` or
+    /// `process_writes`; compared against by [`Watch`] to detect updates.
+    generation: u64,
+    wakers: Vec<Waker>,
+}
+
+impl<T> Inner<T> {
+    /// Record that `value` just changed, waking anyone polling a [`Watch`].
+    fn notify(&mut self) {
+        self.generation += 1;
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+enum State<T> {
+    /// Not yet attached to any list.
+    Vacant,
+    Attached(Inner<T>),
+}
+
+/// A slot for one piece of configuration, keyed by `key`.
+///
+/// Declare one as a `static` and call [`attach`](Self::attach) once the
+/// owning task starts up.
+pub struct StorageListNode<T: 'static> {
+    key: &'static str,
+    state: Mutex<State<T>>,
+    migrations: Mutex<Option<Migration<T>>>,
+}
+
+impl<T: 'static> StorageListNode<T> {
+    /// Create a new, as-yet-unattached node for `key`.
+    pub const fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            state: Mutex::new(State::Vacant),
+            migrations: Mutex::new(None),
+        }
+    }
+
+    /// Register the decode-and-migrate chain used to recover this node's
+    /// value from bytes written by an older schema version. Call this
+    /// before [`attach`](Self::attach). See [`Migration`] for how to build
+    /// one.
+    pub fn with_migrations(&self, migrations: Migration<T>) -> &Self {
+        *self.migrations.lock().unwrap() = Some(migrations);
+        self
+    }
+}
+
+/// A single predecessor decode-and-convert step registered via [`Migration::with`].
+type MigrationStep<T> = Box<dyn Fn(&[u8]) -> Option<T> + Send + Sync>;
+
+/// An ordered decode-and-migrate chain for a [`StorageListNode<T>`].
+///
+/// Register predecessor types newest-to-oldest with [`with`](Self::with);
+/// when the bytes in the store fail to decode as `T` directly,
+/// [`StorageList::process_reads`] walks this chain trying to decode each
+/// predecessor in turn and folding it forward into `T`, so a value several
+/// versions old upgrades to the current schema in one pass.
+pub struct Migration<T: 'static> {
+    steps: Vec<MigrationStep<T>>,
+}
+
+impl<T: 'static> Migration<T> {
+    /// An empty chain; add predecessors with [`with`](Self::with).
+    pub fn new() -> Self {
+        Self { steps: Vec::new() }
+    }
+
+    /// Register `Prev` as a predecessor schema: if decoding as `T` and every
+    /// previously-registered predecessor have failed, try decoding the
+    /// stored bytes as `Prev` and fold it into `T` via `convert`.
+    pub fn with<Prev>(mut self, convert: fn(Prev) -> T) -> Self
+    where
+        Prev: for<'b> Decode<'b, ()> + 'static,
+    {
+        self.steps
+            .push(Box::new(move |bytes| minicbor::decode::<Prev>(bytes).ok().map(convert)));
+        self
+    }
+
+    /// Try every registered predecessor in order, returning the first one
+    /// that successfully decodes and converts.
+    fn decode(&self, bytes: &[u8]) -> Option<T> {
+        self.steps.iter().find_map(|step| step(bytes))
+    }
+}
+
+impl<T: 'static> Default for Migration<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> StorageListNode<T>
+where
+    T: Default + Clone + Encode<()> + for<'b> Decode<'b, ()> + CborLen<()> + Send + Sync + 'static,
+{
+    /// Attach this node to `list`, registering it so that
+    /// [`StorageList::process_reads`] and [`StorageList::process_writes`]
+    /// will pick it up. The node starts out holding `T::default()` until
+    /// the next `process_reads` call loads its real value from the store.
+    pub async fn attach<R: RawMutex>(
+        &'static self,
+        list: &'static StorageList<R>,
+    ) -> Result<ConfigHandle<T>, AttachError> {
+        {
+            let mut guard = self.state.lock().unwrap();
+            if matches!(*guard, State::Vacant) {
+                *guard = State::Attached(Inner {
+                    value: T::default(),
+                    dirty: false,
+                    generation: 0,
+                    wakers: Vec::new(),
+                });
+            }
+        }
+        list.register(self);
+        Ok(ConfigHandle { node: self })
+    }
+}
+
+impl<T> ErasedNode for StorageListNode<T>
+where
+    T: Default + Clone + Encode<()> + for<'b> Decode<'b, ()> + CborLen<()> + Send + Sync + 'static,
+{
+    fn key(&self) -> &'static str {
+        self.key
+    }
+
+    fn load_bytes(&self, bytes: &[u8]) -> bool {
+        // A node with an unflushed write pending (from `handle.write()`, a
+        // not-yet-persisted migration, or a `Transaction` that raced a
+        // concurrent write between `stage()` and `commit()`) must not be
+        // overwritten by what the backend happens to hold right now — the
+        // backend hasn't seen the pending write yet, so reloading here
+        // would silently discard it with no retry. Leave the node as-is;
+        // the pending write will flush on the next `process_writes` and
+        // this reload can be retried after that.
+        if let State::Attached(inner) = &*self.state.lock().unwrap() {
+            if inner.dirty {
+                return false;
+            }
+        }
+
+        // A value written under an older schema won't decode as `T`
+        // directly; fall back to the registered migration chain before
+        // giving up and leaving the node at its current (default) value.
+        let (value, migrated) = match minicbor::decode::<T>(bytes) {
+            Ok(value) => (value, false),
+            Err(_) => {
+                let Some(value) = self
+                    .migrations
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|m| m.decode(bytes))
+                else {
+                    return false;
+                };
+                (value, true)
+            }
+        };
+        let mut guard = self.state.lock().unwrap();
+        match &mut *guard {
+            // Re-check: a write could have landed between the check above
+            // and taking this lock.
+            State::Attached(inner) if inner.dirty => return false,
+            State::Attached(inner) => {
+                // `process_reads` is polled repeatedly; only wake
+                // watchers when the decoded value actually differs from
+                // what's already held, not on every poll that happens to
+                // re-read the same bytes. Compared by re-encoding rather
+                // than requiring `T: PartialEq`.
+                let changed = minicbor::to_vec(&inner.value).ok() != minicbor::to_vec(&value).ok();
+                inner.value = value;
+                // Migrated values are in the new schema but the store
+                // still holds the old bytes; mark dirty so the next
+                // `process_writes` persists them in their upgraded form.
+                inner.dirty = migrated;
+                if changed {
+                    inner.notify();
+                }
+            }
+            State::Vacant => {
+                *guard = State::Attached(Inner {
+                    value,
+                    dirty: migrated,
+                    generation: 0,
+                    wakers: Vec::new(),
+                });
+            }
+        }
+        true
+    }
+
+    fn dirty_bytes(&self) -> Option<Vec<u8>> {
+        let guard = self.state.lock().unwrap();
+        match &*guard {
+            State::Attached(inner) if inner.dirty => minicbor::to_vec(&inner.value).ok(),
+            _ => None,
+        }
+    }
+
+    fn mark_clean(&self) {
+        let mut guard = self.state.lock().unwrap();
+        if let State::Attached(inner) = &mut *guard {
+            inner.dirty = false;
+            inner.notify();
+        }
+    }
+
+}
+
+/// A handle to an attached [`StorageListNode`], returned by
+/// [`StorageListNode::attach`].
+pub struct ConfigHandle<T: 'static> {
+    node: &'static StorageListNode<T>,
+}
+
+impl<T: Clone + 'static> ConfigHandle<T> {
+    /// Type-erased view of this handle's node, for
+    /// [`crate::txn::Transaction`] to stage a batched write against.
+    pub(crate) fn erased(&self) -> &'static dyn ErasedNode
+    where
+        T: Default + Encode<()> + for<'b> Decode<'b, ()> + CborLen<()> + Send + Sync,
+    {
+        self.node
+    }
+
+    /// Read the node's current value.
+    pub fn load(&self) -> T {
+        match &*self.node.state.lock().unwrap() {
+            State::Attached(inner) => inner.value.clone(),
+            State::Vacant => unreachable!("a handle always implies its node is attached"),
+        }
+    }
+
+    /// Stage `value` as the node's new value; it is persisted on the next
+    /// [`StorageList::process_writes`] call.
+    pub fn write(&self, value: &T) {
+        let mut guard = self.node.state.lock().unwrap();
+        match &mut *guard {
+            State::Attached(inner) => {
+                inner.value = value.clone();
+                inner.dirty = true;
+            }
+            State::Vacant => {
+                *guard = State::Attached(Inner {
+                    value: value.clone(),
+                    dirty: true,
+                    generation: 0,
+                    wakers: Vec::new(),
+                });
+            }
+        }
+    }
+
+    /// Returns a future that resolves the next time this node's value
+    /// changes because of a [`StorageList::process_reads`] (another task,
+    /// or this one on a later boot, wrote it) or a
+    /// [`StorageList::process_writes`] (this node's own staged write landed
+    /// in the backend). Unlike [`load`](Self::load), which is a one-shot
+    /// pull, this lets a task react to live reconfiguration.
+    pub fn watch(&self) -> Watch<'_, T> {
+        let seen = match &*self.node.state.lock().unwrap() {
+            State::Attached(inner) => inner.generation,
+            State::Vacant => 0,
+        };
+        Watch {
+            node: self.node,
+            seen,
+        }
+    }
+}
+
+/// Future returned by [`ConfigHandle::watch`].
+pub struct Watch<'a, T: 'static> {
+    node: &'a StorageListNode<T>,
+    seen: u64,
+}
+
+impl<'a, T: Clone + 'static> Future for Watch<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut guard = self.node.state.lock().unwrap();
+        match &mut *guard {
+            State::Attached(inner) if inner.generation != self.seen => {
+                Poll::Ready(inner.value.clone())
+            }
+            State::Attached(inner) => {
+                inner.wakers.push(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Vacant => Poll::Pending,
+        }
+    }
+}
+
+/// Registry of every node currently attached via [`StorageListNode::attach`].
+///
+/// `R` is a [`RawMutex`] type parameter carried only as a marker; the
+/// registry itself is protected by a `std::sync::Mutex` whatever `R` is.
+pub struct StorageList<R: RawMutex> {
+    nodes: Mutex<Vec<&'static dyn ErasedNode>>,
+    _mutex: core::marker::PhantomData<R>,
+}
+
+impl<R: RawMutex> Default for StorageList<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RawMutex> StorageList<R> {
+    /// Create a new, empty list.
+    pub const fn new() -> Self {
+        Self {
+            nodes: Mutex::new(Vec::new()),
+            _mutex: core::marker::PhantomData,
+        }
+    }
+
+    fn register(&self, node: &'static dyn ErasedNode) {
+        self.nodes.lock().unwrap().push(node);
+    }
+
+    /// Reload every attached node from `store`.
+    ///
+    /// Also finishes applying any [`crate::txn::Transaction`] that was
+    /// still mid-commit when the process last stopped, before the
+    /// per-node reload below can observe a half-applied batch. A node
+    /// whose `fetch` fails is left exactly as it was (still holding its
+    /// last known value, or `T::default()` if never loaded) and the error
+    /// is logged rather than silently dropped, so a flaky read doesn't
+    /// look indistinguishable from "key not present". Likewise, a node
+    /// with an unflushed `handle.write()` pending is left alone rather
+    /// than reloaded, so polling for other tasks' updates never clobbers
+    /// a write this task hasn't flushed yet; see [`ErasedNode::load_bytes`].
+    pub async fn process_reads<S: ConfigStore>(&self, store: &mut S)
+    where
+        S::Error: core::fmt::Debug,
+    {
+        if let Err(e) = crate::txn::recover(store).await {
+            eprintln!("process_reads: transaction recovery failed: {e:?}");
+        }
+
+        let nodes: Vec<&'static dyn ErasedNode> = self.nodes.lock().unwrap().clone();
+        for node in nodes {
+            match store.fetch(node.key()).await {
+                Ok(Some(bytes)) => {
+                    node.load_bytes(bytes);
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("process_reads: fetch({:?}) failed: {e:?}", node.key()),
+            }
+        }
+    }
+
+    /// Flush every node written since the last call to `store`.
+    ///
+    /// A node is only marked clean once `store.store` actually succeeds;
+    /// on failure the node stays dirty (and the error is logged) so the
+    /// next `process_writes` retries it instead of the write being lost.
+    pub async fn process_writes<S: ConfigStore>(&self, store: &mut S)
+    where
+        S::Error: core::fmt::Debug,
+    {
+        let nodes: Vec<&'static dyn ErasedNode> = self.nodes.lock().unwrap().clone();
+        for node in nodes {
+            let Some(bytes) = node.dirty_bytes() else {
+                continue;
+            };
+            match store.store(node.key(), &bytes).await {
+                Ok(()) => node.mark_clean(),
+                Err(e) => eprintln!("process_writes: store({:?}) failed: {e:?}", node.key()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Default, Clone, PartialEq, Encode, Decode, CborLen)]
+    struct Cfg {
+        #[n(0)]
+        value: u32,
+    }
+
+    #[tokio::test]
+    async fn watch_only_fires_on_actual_change() {
+        static LIST: StorageList<CriticalSectionRawMutex> = StorageList::new();
+        static NODE: StorageListNode<Cfg> = StorageListNode::new("watch/only-on-change");
+
+        let handle = NODE.attach(&LIST).await.unwrap();
+
+        // Re-loading the exact bytes the node already holds (as a
+        // `process_reads` poll would when nothing actually changed) must
+        // not wake an already-registered watch.
+        let same = minicbor::to_vec(Cfg::default()).unwrap();
+        assert!(NODE.load_bytes(&same));
+        let stale_watch = handle.watch();
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(20), stale_watch)
+                .await
+                .is_err(),
+            "watch resolved even though the stored value didn't change"
+        );
+
+        // Loading a value that actually differs must wake it.
+        let live_watch = handle.watch();
+        let different = minicbor::to_vec(&Cfg { value: 1 }).unwrap();
+        assert!(NODE.load_bytes(&different));
+        let woken = tokio::time::timeout(std::time::Duration::from_millis(20), live_watch)
+            .await
+            .expect("watch should resolve once the value changes");
+        assert_eq!(woken, Cfg { value: 1 });
+    }
+
+    #[tokio::test]
+    async fn load_bytes_does_not_clobber_a_pending_write() {
+        static LIST: StorageList<CriticalSectionRawMutex> = StorageList::new();
+        static NODE: StorageListNode<Cfg> = StorageListNode::new("load/dont-clobber-pending-write");
+
+        let handle = NODE.attach(&LIST).await.unwrap();
+        handle.write(&Cfg { value: 99 });
+
+        // Reloading stale backend bytes (as a `process_reads` poll for some
+        // *other* task's update would) must not stomp this task's own
+        // unflushed write.
+        let stale = minicbor::to_vec(Cfg { value: 1 }).unwrap();
+        assert!(!NODE.load_bytes(&stale));
+        assert_eq!(handle.load(), Cfg { value: 99 });
+        assert_eq!(NODE.dirty_bytes(), Some(minicbor::to_vec(Cfg { value: 99 }).unwrap()));
+    }
+}
@@ -0,0 +1,264 @@
+//! Log-structured journal with periodic checkpointing.
+//!
+//! [`StorageList::process_writes`](crate::intrusive::StorageList::process_writes)
+//! persists a node by overwriting its key directly, which means a node
+//! that toggles a single field rewrites its whole value on every flush —
+//! fine for a `HashMap`, bad for NOR flash endurance. [`JournaledStore`]
+//! wraps any [`ConfigStore`] and turns it into an append-only log instead:
+//! writes append small records under synthetic `"__journal/{seq}"` keys,
+//! and [`JournaledStore::replay`] folds them back into a key/value view by
+//! replaying records in ascending `seq` order, latest record per key
+//! winning. Every `checkpoint_interval` appended records (configurable via
+//! [`JournaledStore::with_checkpoint_interval`]; [`CHECKPOINT_INTERVAL`] by
+//! default), the current view is folded into a single checkpoint record
+//! and every older record is reclaimed.
+
+use std::collections::BTreeMap;
+
+use minicbor::{CborLen, Decode, Encode};
+
+use crate::hashmap::ConfigStore;
+
+/// Default number of appended records between automatic checkpoints; see
+/// [`JournaledStore::with_checkpoint_interval`] to override it.
+pub const CHECKPOINT_INTERVAL: u64 = 64;
+
+const JOURNAL_PREFIX: &str = "__journal/";
+
+#[derive(Debug, Clone, Encode, Decode, CborLen)]
+struct Record {
+    #[n(0)]
+    seq: u64,
+    #[n(1)]
+    op: Op,
+}
+
+#[derive(Debug, Clone, Encode, Decode, CborLen)]
+enum Op {
+    #[n(0)]
+    Put(#[n(0)] String, #[n(1)] Vec<u8>),
+    #[n(1)]
+    Remove(#[n(0)] String),
+    /// Supersedes every record with `seq` less than or equal to its own.
+    #[n(2)]
+    Checkpoint(#[n(0)] Vec<(String, Vec<u8>)>),
+}
+
+/// A [`ConfigStore`] adapter that journals writes instead of rewriting keys
+/// in place. See the module docs for the on-disk layout.
+pub struct JournaledStore<S> {
+    inner: S,
+    seq: u64,
+    since_checkpoint: u64,
+    checkpoint_interval: u64,
+    cache: BTreeMap<String, Vec<u8>>,
+}
+
+impl<S: ConfigStore> JournaledStore<S> {
+    /// Wrap `inner`, checkpointing every [`CHECKPOINT_INTERVAL`] appended
+    /// records. Call [`replay`](Self::replay) once before using the store
+    /// so the in-memory view reflects whatever was already journaled.
+    pub fn new(inner: S) -> Self {
+        Self::with_checkpoint_interval(inner, CHECKPOINT_INTERVAL)
+    }
+
+    /// Wrap `inner` like [`new`](Self::new), but checkpoint every
+    /// `checkpoint_interval` appended records instead of the default
+    /// [`CHECKPOINT_INTERVAL`]. A smaller interval checkpoints (and so
+    /// reclaims old records) more eagerly at the cost of more frequent
+    /// full-state writes; a larger one trades more replay work after a
+    /// restart for fewer of those writes.
+    pub fn with_checkpoint_interval(inner: S, checkpoint_interval: u64) -> Self {
+        Self {
+            inner,
+            seq: 0,
+            since_checkpoint: 0,
+            checkpoint_interval,
+            cache: BTreeMap::new(),
+        }
+    }
+
+    fn record_key(seq: u64) -> String {
+        format!("{JOURNAL_PREFIX}{seq:020}")
+    }
+
+    /// Replay every record in `inner`, in ascending `seq` order, folding
+    /// them into the in-memory view: the latest record for a given key
+    /// wins, and a checkpoint fully replaces the view built so far. A
+    /// record that fails to decode is treated as a torn write from a crash
+    /// mid-append and, along with everything after it, is ignored — since
+    /// records are only ever appended in increasing `seq` order, nothing
+    /// past the first torn one can be valid either.
+    pub async fn replay(&mut self) -> Result<(), S::Error> {
+        let mut keys: Vec<String> = self
+            .inner
+            .list_keys()
+            .await?
+            .into_iter()
+            .filter(|k| k.starts_with(JOURNAL_PREFIX))
+            .collect();
+        keys.sort();
+
+        let mut cache = BTreeMap::new();
+        let mut max_seq = 0;
+        // Records appended since the last checkpoint seen during replay;
+        // reset every time a `Checkpoint` record is folded in, so this
+        // ends up counting exactly the tail `process_writes` would still
+        // need to fold into a checkpoint of its own.
+        let mut since_checkpoint = 0;
+        for key in &keys {
+            let Some(bytes) = self.inner.fetch(key).await? else {
+                continue;
+            };
+            let Ok(record) = minicbor::decode::<Record>(bytes) else {
+                break;
+            };
+            max_seq = max_seq.max(record.seq);
+            match record.op {
+                Op::Put(k, v) => {
+                    cache.insert(k, v);
+                    since_checkpoint += 1;
+                }
+                Op::Remove(k) => {
+                    cache.remove(&k);
+                    since_checkpoint += 1;
+                }
+                Op::Checkpoint(snapshot) => {
+                    cache = snapshot.into_iter().collect();
+                    since_checkpoint = 0;
+                }
+            }
+        }
+
+        self.cache = cache;
+        self.seq = max_seq;
+        self.since_checkpoint = since_checkpoint;
+        Ok(())
+    }
+
+    async fn append(&mut self, op: Op) -> Result<(), S::Error> {
+        self.seq += 1;
+        let record = Record { seq: self.seq, op };
+        let bytes = minicbor::to_vec(&record).expect("record always encodes");
+        self.inner.store(&Self::record_key(record.seq), &bytes).await?;
+
+        self.since_checkpoint += 1;
+        if self.since_checkpoint >= self.checkpoint_interval {
+            self.checkpoint().await?;
+        }
+        Ok(())
+    }
+
+    /// Fold the current view into a single checkpoint record, then reclaim
+    /// every record older than it. The checkpoint is written before the
+    /// old records are erased, so a crash mid-reclaim leaves either the old
+    /// records or the new checkpoint fully valid — never neither.
+    pub async fn checkpoint(&mut self) -> Result<(), S::Error> {
+        let snapshot: Vec<(String, Vec<u8>)> = self
+            .cache
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        self.seq += 1;
+        let record = Record {
+            seq: self.seq,
+            op: Op::Checkpoint(snapshot),
+        };
+        let checkpoint_key = Self::record_key(record.seq);
+        let bytes = minicbor::to_vec(&record).expect("record always encodes");
+
+        let stale_keys: Vec<String> = self
+            .inner
+            .list_keys()
+            .await?
+            .into_iter()
+            .filter(|k| k.starts_with(JOURNAL_PREFIX) && *k != checkpoint_key)
+            .collect();
+
+        self.inner.store(&checkpoint_key, &bytes).await?;
+        for key in stale_keys {
+            self.inner.remove(&key).await?;
+        }
+        self.since_checkpoint = 0;
+        Ok(())
+    }
+}
+
+impl<S: ConfigStore> ConfigStore for JournaledStore<S> {
+    type Error = S::Error;
+
+    async fn fetch(&self, key: &str) -> Result<Option<&[u8]>, Self::Error> {
+        Ok(self.cache.get(key).map(Vec::as_slice))
+    }
+
+    async fn store(&mut self, key: &str, val: &[u8]) -> Result<(), Self::Error> {
+        self.cache.insert(key.to_string(), val.to_vec());
+        self.append(Op::Put(key.to_string(), val.to_vec())).await
+    }
+
+    async fn remove(&mut self, key: &str) -> Result<(), Self::Error> {
+        self.cache.remove(key);
+        self.append(Op::Remove(key.to_string())).await
+    }
+
+    async fn list_keys(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.cache.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn replay_restores_since_checkpoint() {
+        let mut store = JournaledStore::new(HashMap::<String, Vec<u8>>::new());
+        for i in 0..10u32 {
+            store
+                .store(&format!("key/{i}"), &i.to_le_bytes())
+                .await
+                .unwrap();
+        }
+        assert_eq!(store.since_checkpoint, 10);
+
+        // Simulate a restart: a fresh `JournaledStore` over the same
+        // backing data, with its counters rebuilt purely from replay.
+        let mut restarted = JournaledStore::new(store.inner);
+        restarted.replay().await.unwrap();
+        assert_eq!(restarted.since_checkpoint, 10);
+    }
+
+    #[tokio::test]
+    async fn replay_resets_since_checkpoint_after_checkpoint_record() {
+        let mut store = JournaledStore::new(HashMap::<String, Vec<u8>>::new());
+        for i in 0..5u32 {
+            store
+                .store(&format!("key/{i}"), &i.to_le_bytes())
+                .await
+                .unwrap();
+        }
+        store.checkpoint().await.unwrap();
+        store.store("key/after", &[1]).await.unwrap();
+
+        let mut restarted = JournaledStore::new(store.inner);
+        restarted.replay().await.unwrap();
+        assert_eq!(restarted.since_checkpoint, 1);
+    }
+
+    #[tokio::test]
+    async fn with_checkpoint_interval_overrides_the_default() {
+        let mut store =
+            JournaledStore::with_checkpoint_interval(HashMap::<String, Vec<u8>>::new(), 3);
+        for i in 0..3u32 {
+            store
+                .store(&format!("key/{i}"), &i.to_le_bytes())
+                .await
+                .unwrap();
+        }
+        // A checkpoint should have fired automatically at the configured
+        // interval instead of the default `CHECKPOINT_INTERVAL` of 64.
+        assert_eq!(store.since_checkpoint, 0);
+    }
+}
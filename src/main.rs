@@ -1,12 +1,16 @@
 use std::{collections::HashMap, time::Duration};
 
-use intrusive::{StorageList, StorageListNode};
+use hashmap::ConfigStore;
+use intrusive::{Migration, StorageList, StorageListNode};
+use journal::JournaledStore;
 use minicbor::{CborLen, Decode, Encode};
 use mutex::raw_impls::cs::CriticalSectionRawMutex;
 use tokio::time::sleep;
 
 pub mod hashmap;
 pub mod intrusive;
+pub mod journal;
+pub mod txn;
 
 #[tokio::main]
 async fn main() {
@@ -14,32 +18,65 @@ async fn main() {
     tokio::task::spawn(task_2(&GLOBAL_LIST));
     tokio::task::spawn(task_3(&GLOBAL_LIST));
 
-    let mut flash = HashMap::<String, Vec<u8>>::new();
-    flash.insert(
-        "encabulator/config".to_string(),
-        minicbor::to_vec(&EncabulatorConfigV1 { polarity: true }).unwrap(),
-    );
-    flash.insert(
-        "grammeter/config".to_string(),
-        minicbor::to_vec(&GrammeterConfig { radiation: 100.0 }).unwrap(),
-    );
+    let mut flash = JournaledStore::new(HashMap::<String, Vec<u8>>::new());
+    flash.replay().await.unwrap();
+    flash
+        .store(
+            "encabulator/config",
+            &minicbor::to_vec(&EncabulatorConfigV1 { polarity: true }).unwrap(),
+        )
+        .await
+        .unwrap();
+    flash
+        .store(
+            "grammeter/config",
+            &minicbor::to_vec(&GrammeterConfig { radiation: 100.0 }).unwrap(),
+        )
+        .await
+        .unwrap();
     // no positron config
 
     // give time for tasks to attach
     sleep(Duration::from_millis(100)).await;
     // process reads
-    GLOBAL_LIST.process_reads(&flash);
+    GLOBAL_LIST.process_reads(&mut flash).await;
+
+    // Demonstrate a multi-node atomic commit: sensor_a/sensor_b are only
+    // ever updated together, so a Transaction is used instead of two
+    // independent `write()` calls that `process_writes` could split
+    // across a crash.
+    let sensor_a = SENSOR_A.attach(&GLOBAL_LIST).await.unwrap();
+    let sensor_b = SENSOR_B.attach(&GLOBAL_LIST).await.unwrap();
+    let mut txn = GLOBAL_LIST.begin();
+    txn.stage(&sensor_a, &SensorConfig { gain: 5 });
+    txn.stage(&sensor_b, &SensorConfig { gain: 5 });
+    txn.commit(&mut flash).await.unwrap();
+    println!(
+        "T4 committed sensor_a={:?} sensor_b={:?}",
+        sensor_a.load(),
+        sensor_b.load()
+    );
 
     for _ in 0..10 {
         sleep(Duration::from_secs(1)).await;
-        let mut flash2 = HashMap::<String, Vec<u8>>::new();
-        GLOBAL_LIST.process_writes(&mut flash2);
-        println!("NEW WRITES: {flash2:?}");
+        GLOBAL_LIST.process_writes(&mut flash).await;
     }
 }
 
 static GLOBAL_LIST: StorageList<CriticalSectionRawMutex> = StorageList::new();
 
+//
+// TASK 4: Paired config only ever written together, via a Transaction
+//
+#[derive(Debug, Default, Clone, PartialEq, Encode, Decode, CborLen)]
+struct SensorConfig {
+    #[n(0)]
+    gain: u8,
+}
+
+static SENSOR_A: StorageListNode<SensorConfig> = StorageListNode::new("sensor/a");
+static SENSOR_B: StorageListNode<SensorConfig> = StorageListNode::new("sensor/b");
+
 //
 // TASK 1: Has config, but an old version
 //
@@ -60,6 +97,12 @@ struct EncabulatorConfigV2 {
 static ENCAB_CONFIG: StorageListNode<EncabulatorConfigV2> =
     StorageListNode::new("encabulator/config");
 async fn task_1(list: &'static StorageList<CriticalSectionRawMutex>) {
+    ENCAB_CONFIG.with_migrations(Migration::new().with::<EncabulatorConfigV1>(|v1| {
+        EncabulatorConfigV2 {
+            polarity: v1.polarity,
+            spinrate: None,
+        }
+    }));
     let config_handle = ENCAB_CONFIG.attach(list).await.unwrap();
     let data: EncabulatorConfigV2 = config_handle.load();
     println!("T1 Got {data:?}");
@@ -68,6 +111,10 @@ async fn task_1(list: &'static StorageList<CriticalSectionRawMutex>) {
         polarity: true,
         spinrate: Some(100),
     });
+    loop {
+        let data = config_handle.watch().await;
+        println!("T1 saw update: {data:?}");
+    }
 }
 
 //
@@ -86,6 +133,10 @@ async fn task_2(list: &'static StorageList<CriticalSectionRawMutex>) {
     println!("T2 Got {data:?}");
     sleep(Duration::from_secs(3)).await;
     config_handle.write(&GrammeterConfig { radiation: 200.0 });
+    loop {
+        let data = config_handle.watch().await;
+        println!("T2 saw update: {data:?}");
+    }
 }
 
 //
@@ -123,4 +174,8 @@ async fn task_3(list: &'static StorageList<CriticalSectionRawMutex>) {
         down: 25,
         strange: 108,
     });
+    loop {
+        let data = config_handle.watch().await;
+        println!("T3 saw update: {data:?}");
+    }
 }
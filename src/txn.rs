@@ -0,0 +1,181 @@
+//! Atomic multi-node transactional commits.
+//!
+//! Normally a task calls [`ConfigHandle::write`](crate::intrusive::ConfigHandle::write)
+//! and waits for the next periodic
+//! [`StorageList::process_writes`](crate::intrusive::StorageList::process_writes)
+//! to flush it, so a crash between flushing two related nodes can leave
+//! them inconsistent with each other. A [`Transaction`] lets a task stage
+//! several nodes' next values and commit them to the backend as a single
+//! all-or-nothing unit instead, analogous to a batch key-value write.
+
+use minicbor::{CborLen, Decode, Encode};
+
+use crate::hashmap::ConfigStore;
+use crate::intrusive::{ConfigHandle, ErasedNode, RawMutex, StorageList};
+
+/// Key the in-flight commit marker is written under; see [`recover`].
+const MARKER_KEY: &str = "__txn/pending";
+
+#[derive(Debug, Clone, Encode, Decode, CborLen)]
+struct Marker {
+    #[n(0)]
+    writes: Vec<(String, Vec<u8>)>,
+}
+
+/// A batch of staged node writes, built with [`StorageList::begin`].
+pub struct Transaction<'a, R: RawMutex> {
+    #[allow(dead_code)]
+    list: &'a StorageList<R>,
+    staged: Vec<(&'static dyn ErasedNode, Vec<u8>)>,
+}
+
+impl<R: RawMutex> StorageList<R> {
+    /// Begin a transaction batching writes to several attached nodes.
+    pub fn begin(&self) -> Transaction<'_, R> {
+        Transaction {
+            list: self,
+            staged: Vec::new(),
+        }
+    }
+}
+
+impl<'a, R: RawMutex> Transaction<'a, R> {
+    /// Stage `value` as `handle`'s next value. Nothing is visible via
+    /// `load()`/`watch()`, and nothing is written to the backend, until
+    /// [`commit`](Self::commit) succeeds.
+    pub fn stage<T>(&mut self, handle: &ConfigHandle<T>, value: &T)
+    where
+        T: Default + Clone + Encode<()> + for<'b> Decode<'b, ()> + CborLen<()> + Send + Sync + 'static,
+    {
+        let bytes = minicbor::to_vec(value).expect("value always encodes");
+        self.staged.push((handle.erased(), bytes));
+    }
+
+    /// Commit every staged write to `store` as a single all-or-nothing
+    /// unit: the whole batch is written under [`MARKER_KEY`] first, then
+    /// each key is written for real, then the marker is cleared. If the
+    /// process crashes anywhere in between, the marker survives and the
+    /// next [`StorageList::process_reads`] call finishes applying it via
+    /// [`recover`], so a reader never observes only part of the batch
+    /// having landed.
+    ///
+    /// Each node's in-memory value is updated to match what was just
+    /// persisted via [`ErasedNode::load_bytes`], the same path a normal
+    /// [`StorageList::process_reads`] uses, so a node comes out of a
+    /// commit clean rather than dirty — there's nothing left for the next
+    /// `process_writes` to redundantly flush. If a `handle.write()` races
+    /// in between [`stage`](Self::stage) and `commit`, `load_bytes` finds
+    /// the node already dirty and leaves it alone rather than reloading
+    /// it, so the concurrent write survives and flushes on a later
+    /// `process_writes` instead of being silently overwritten by the
+    /// (now stale) staged bytes.
+    pub async fn commit<S: ConfigStore>(self, store: &mut S) -> Result<(), S::Error> {
+        let writes: Vec<(String, Vec<u8>)> = self
+            .staged
+            .iter()
+            .map(|(node, bytes)| (node.key().to_string(), bytes.clone()))
+            .collect();
+        apply_writes(store, &writes).await?;
+
+        for (node, bytes) in &self.staged {
+            node.load_bytes(bytes);
+        }
+        Ok(())
+    }
+}
+
+async fn apply_writes<S: ConfigStore>(
+    store: &mut S,
+    writes: &[(String, Vec<u8>)],
+) -> Result<(), S::Error> {
+    let marker = Marker {
+        writes: writes.to_vec(),
+    };
+    let bytes = minicbor::to_vec(&marker).expect("marker always encodes");
+    store.store(MARKER_KEY, &bytes).await?;
+
+    for (key, val) in writes {
+        store.store(key, val).await?;
+    }
+    store.remove(MARKER_KEY).await
+}
+
+/// Finish applying any transaction marker left behind by a crash mid-commit.
+///
+/// Called at the start of every
+/// [`StorageList::process_reads`](crate::intrusive::StorageList::process_reads);
+/// a no-op if no previous commit was interrupted.
+pub(crate) async fn recover<S: ConfigStore>(store: &mut S) -> Result<(), S::Error> {
+    let Some(bytes) = store.fetch(MARKER_KEY).await?.map(<[u8]>::to_vec) else {
+        return Ok(());
+    };
+    let Ok(marker) = minicbor::decode::<Marker>(&bytes) else {
+        // Torn marker write from a crash mid-append; nothing consistent
+        // was staged, so there's nothing to recover.
+        return store.remove(MARKER_KEY).await;
+    };
+    apply_writes(store, &marker.writes).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mutex::raw_impls::cs::CriticalSectionRawMutex;
+
+    use super::*;
+    use crate::intrusive::StorageListNode;
+
+    #[derive(Debug, Default, Clone, PartialEq, Encode, Decode, CborLen)]
+    struct Cfg {
+        #[n(0)]
+        value: u32,
+    }
+
+    #[tokio::test]
+    async fn commit_leaves_node_clean_instead_of_restaging_dirty() {
+        static LIST: StorageList<CriticalSectionRawMutex> = StorageList::new();
+        static NODE: StorageListNode<Cfg> = StorageListNode::new("txn/commit-clean");
+
+        let handle = NODE.attach(&LIST).await.unwrap();
+        let mut store = HashMap::<String, Vec<u8>>::new();
+
+        let mut txn = LIST.begin();
+        txn.stage(&handle, &Cfg { value: 7 });
+        txn.commit(&mut store).await.unwrap();
+
+        // The committed value is visible immediately, without waiting on
+        // `process_reads`.
+        assert_eq!(handle.load(), Cfg { value: 7 });
+        // And the node must not come out of the commit marked dirty: the
+        // bytes are already durably persisted, so there's nothing left for
+        // `process_writes` to redundantly flush.
+        assert_eq!(NODE.dirty_bytes(), None);
+    }
+
+    #[tokio::test]
+    async fn commit_preserves_a_write_that_races_it() {
+        static LIST: StorageList<CriticalSectionRawMutex> = StorageList::new();
+        static NODE: StorageListNode<Cfg> = StorageListNode::new("txn/commit-preserves-race");
+
+        let handle = NODE.attach(&LIST).await.unwrap();
+        let mut store = HashMap::<String, Vec<u8>>::new();
+
+        let mut txn = LIST.begin();
+        txn.stage(&handle, &Cfg { value: 1 });
+
+        // A write lands in between staging and committing.
+        handle.write(&Cfg { value: 42 });
+
+        txn.commit(&mut store).await.unwrap();
+
+        // The concurrent write must survive the commit rather than being
+        // silently discarded in favor of the now-stale staged value, and
+        // must still be dirty so a later `process_writes` flushes it.
+        assert_eq!(handle.load(), Cfg { value: 42 });
+        assert_eq!(
+            NODE.dirty_bytes(),
+            Some(minicbor::to_vec(Cfg { value: 42 }).unwrap())
+        );
+    }
+}